@@ -0,0 +1,131 @@
+use libc::c_int;
+
+use {raw, Error, Session, HostKeyType, TypeRsa, TypeDss, TypeUnknown};
+
+/// A set of known hosts for a session, loaded from and savable back to an
+/// OpenSSH-format `known_hosts` file.
+///
+/// Obtained via `Session::known_hosts`.
+pub struct KnownHosts<'sess> {
+    raw: *mut raw::LIBSSH2_KNOWNHOST,
+    sess: &'sess Session,
+}
+
+/// The result of checking a host key against a `KnownHosts` set.
+#[deriving(PartialEq, Eq, Show)]
+pub enum KnownHostCheckResult {
+    /// Hosts and keys match.
+    Match,
+    /// Host was found, but the keys didn't match.
+    Mismatch,
+    /// No host match was found at all.
+    NotFound,
+    /// Something bogus happened, unable to check.
+    Failure,
+}
+
+impl<'sess> KnownHosts<'sess> {
+    /// Takes ownership of the given raw pointer and wraps it, tying it to
+    /// the lifetime of the session provided.
+    ///
+    /// This is unsafe as there is no guarantee about the validity of `raw`.
+    pub unsafe fn from_raw(sess: &'sess Session,
+                           raw: *mut raw::LIBSSH2_KNOWNHOST) -> KnownHosts<'sess> {
+        KnownHosts {
+            raw: raw,
+            sess: sess,
+        }
+    }
+
+    /// Read hosts and keys from an OpenSSH-style `known_hosts` file,
+    /// appending them to the set already in memory.
+    pub fn read_file(&self, filename: &str) -> Result<(), Error> {
+        let filename = filename.to_c_str();
+        unsafe {
+            let rc = raw::libssh2_knownhost_readfile(self.raw,
+                                                      filename.as_ptr(),
+                                                      raw::LIBSSH2_KNOWNHOST_FILE_OPENSSH);
+            if rc < 0 {
+                self.sess.rc(rc).map(|()| ())
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    /// Write all the currently known hosts out to an OpenSSH-style
+    /// `known_hosts` file, overwriting the destination.
+    pub fn write_file(&self, filename: &str) -> Result<(), Error> {
+        let filename = filename.to_c_str();
+        unsafe {
+            self.sess.rc(raw::libssh2_knownhost_writefile(self.raw,
+                                                           filename.as_ptr(),
+                                                           raw::LIBSSH2_KNOWNHOST_FILE_OPENSSH))
+        }
+    }
+
+    /// Add a host and its associated key to the collection of known hosts.
+    ///
+    /// The `key` is expected to be the raw key bytes as returned by
+    /// `Session::host_key`, and `comment` is an optional freeform comment
+    /// to associate with the entry.
+    pub fn add(&self, host: &str, key: &[u8], comment: &str,
+               key_type: HostKeyType) -> Result<(), Error> {
+        let host = host.to_c_str();
+        let comment = comment.to_c_str();
+        let type_mask = raw::LIBSSH2_KNOWNHOST_TYPE_PLAIN |
+                        raw::LIBSSH2_KNOWNHOST_KEYENC_RAW |
+                        key_mask(key_type);
+        unsafe {
+            self.sess.rc(raw::libssh2_knownhost_addc(self.raw,
+                                                      host.as_ptr(),
+                                                      0 as *const _,
+                                                      key.as_ptr() as *const _,
+                                                      key.len() as ::libc::size_t,
+                                                      comment.as_ptr(),
+                                                      comment.len() as ::libc::size_t,
+                                                      type_mask as c_int,
+                                                      0 as *mut _))
+        }
+    }
+
+    /// Check a host and key against the set of known hosts, returning
+    /// whether they match, mismatch, or were not found at all.
+    pub fn check(&self, host: &str, port: u16, key: &[u8],
+                 key_type: HostKeyType) -> KnownHostCheckResult {
+        let host = host.to_c_str();
+        let type_mask = raw::LIBSSH2_KNOWNHOST_TYPE_PLAIN |
+                        raw::LIBSSH2_KNOWNHOST_KEYENC_RAW |
+                        key_mask(key_type);
+        unsafe {
+            let rc = raw::libssh2_knownhost_checkp(self.raw,
+                                                    host.as_ptr(),
+                                                    port as c_int,
+                                                    key.as_ptr() as *const _,
+                                                    key.len() as ::libc::size_t,
+                                                    type_mask as c_int,
+                                                    0 as *mut _);
+            match rc {
+                raw::LIBSSH2_KNOWNHOST_CHECK_MATCH => Match,
+                raw::LIBSSH2_KNOWNHOST_CHECK_MISMATCH => Mismatch,
+                raw::LIBSSH2_KNOWNHOST_CHECK_NOTFOUND => NotFound,
+                _ => Failure,
+            }
+        }
+    }
+}
+
+fn key_mask(kind: HostKeyType) -> c_int {
+    match kind {
+        TypeRsa => raw::LIBSSH2_KNOWNHOST_KEY_SSHRSA,
+        TypeDss => raw::LIBSSH2_KNOWNHOST_KEY_SSHDSS,
+        TypeUnknown => raw::LIBSSH2_KNOWNHOST_KEY_UNKNOWN,
+    }
+}
+
+#[unsafe_destructor]
+impl<'sess> Drop for KnownHosts<'sess> {
+    fn drop(&mut self) {
+        unsafe { raw::libssh2_knownhost_free(self.raw) }
+    }
+}
@@ -0,0 +1,44 @@
+use {raw, Error, Session, Channel};
+
+/// A listener for incoming forwarded connections, created by
+/// `Session::channel_forward_listen`.
+///
+/// Dropping the listener cancels the forwarding request on the remote end.
+pub struct Listener<'sess> {
+    raw: *mut raw::LIBSSH2_LISTENER,
+    sess: &'sess Session,
+}
+
+impl<'sess> Listener<'sess> {
+    /// Takes ownership of the given raw pointer and wraps it, tying it to
+    /// the lifetime of the session provided.
+    ///
+    /// This is unsafe as there is no guarantee about the validity of `raw`.
+    pub unsafe fn from_raw(sess: &'sess Session,
+                           raw: *mut raw::LIBSSH2_LISTENER) -> Listener<'sess> {
+        Listener {
+            raw: raw,
+            sess: sess,
+        }
+    }
+
+    /// Block until the remote end forwards a new connection, returning a
+    /// channel connected to it.
+    pub fn accept(&mut self) -> Result<Channel, Error> {
+        unsafe {
+            let ret = raw::libssh2_channel_forward_accept(self.raw);
+            if ret.is_null() {
+                Err(Error::last_error(self.sess).unwrap())
+            } else {
+                Ok(Channel::from_raw(self.sess, ret))
+            }
+        }
+    }
+}
+
+#[unsafe_destructor]
+impl<'sess> Drop for Listener<'sess> {
+    fn drop(&mut self) {
+        unsafe { raw::libssh2_channel_forward_cancel(self.raw); }
+    }
+}
@@ -1,11 +1,32 @@
 use std::kinds::marker;
 use std::mem;
+use std::ptr;
 use std::raw as stdraw;
 use std::str;
-use libc::{c_uint, c_int, c_void, c_long};
+use libc::{c_uint, c_int, c_void, c_long, c_char};
 
 use {raw, Error, DisconnectCode, ByApplication, SessionFlag, HostKeyType};
-use {MethodType, Agent, Channel};
+use {MethodType, Agent, Channel, KnownHosts, Sftp, FileStat, Listener};
+
+/// The hash algorithm used to compute a `Session::host_key_hash` digest.
+#[deriving(PartialEq, Eq, Show)]
+pub enum HashType {
+    /// A 16-byte MD5 digest.
+    HashMd5 = raw::LIBSSH2_HOSTKEY_HASH_MD5 as int,
+    /// A 20-byte SHA1 digest.
+    HashSha1 = raw::LIBSSH2_HOSTKEY_HASH_SHA1 as int,
+}
+
+/// Describes which direction(s) of the socket a non-blocking session is
+/// currently waiting on, as returned by `Session::block_directions`.
+pub struct BlockDirections {
+    /// The session needs the socket to become readable before it can make
+    /// further progress.
+    pub inbound: bool,
+    /// The session needs the socket to become writable before it can make
+    /// further progress.
+    pub outbound: bool,
+}
 
 pub struct Session {
     raw: *mut raw::LIBSSH2_SESSION,
@@ -133,6 +154,22 @@ impl Session {
         unsafe { raw::libssh2_session_set_timeout(self.raw, timeout_ms) }
     }
 
+    /// Find out which direction(s) a non-blocking session is currently
+    /// blocked on.
+    ///
+    /// When `set_blocking(false)` is in effect, libssh2 calls may return
+    /// `LIBSSH2_ERROR_EAGAIN` instead of waiting; this tells the caller
+    /// whether to wait for the socket to become readable, writable, or
+    /// both before retrying, so it can be driven correctly from an
+    /// external `select`/`poll`/reactor loop instead of busy-spinning.
+    pub fn block_directions(&self) -> BlockDirections {
+        let dirs = unsafe { raw::libssh2_session_block_directions(self.raw) };
+        BlockDirections {
+            inbound: dirs & raw::LIBSSH2_SESSION_BLOCK_INBOUND != 0,
+            outbound: dirs & raw::LIBSSH2_SESSION_BLOCK_OUTBOUND != 0,
+        }
+    }
+
     /// Get the remote key.
     ///
     /// Returns `None` if something went wrong.
@@ -155,6 +192,29 @@ impl Session {
         }
     }
 
+    /// Get the computed digest of the remote host's key.
+    ///
+    /// Returns `None` if the hash is not yet available (e.g. before
+    /// `handshake` completes).
+    pub fn host_key_hash(&self, hash: HashType) -> Option<&[u8]> {
+        let len = match hash {
+            HashMd5 => 16u,
+            HashSha1 => 20u,
+        };
+        unsafe {
+            let ret = raw::libssh2_hostkey_hash(self.raw, hash as c_int);
+            if ret.is_null() {
+                None
+            } else {
+                let data: &[u8] = mem::transmute(stdraw::Slice {
+                    data: ret as *const u8,
+                    len: len,
+                });
+                Some(data)
+            }
+        }
+    }
+
     /// Set preferred key exchange method
     ///
     /// The preferences provided are a comma delimited list of preferred methods
@@ -217,6 +277,39 @@ impl Session {
         }
     }
 
+    /// Init an ssh-knownhosts handle.
+    ///
+    /// The returned handle can be used to read, add, check, and write
+    /// `known_hosts`-style host key entries, letting callers verify a
+    /// server's host key (as returned by `host_key`) before trusting it.
+    pub fn known_hosts(&self) -> Result<KnownHosts, Error> {
+        unsafe {
+            let ptr = raw::libssh2_knownhost_init(self.raw);
+            if ptr.is_null() {
+                Err(Error::last_error(self).unwrap())
+            } else {
+                Ok(KnownHosts::from_raw(self, ptr))
+            }
+        }
+    }
+
+    /// Init an SFTP handle.
+    ///
+    /// The returned handle provides full remote filesystem access: opening
+    /// and creating files, listing directories, and the usual stat/rename/
+    /// remove family of operations, all without having to script a shell
+    /// over a raw channel.
+    pub fn sftp(&self) -> Result<Sftp, Error> {
+        unsafe {
+            let ptr = raw::libssh2_sftp_init(self.raw);
+            if ptr.is_null() {
+                Err(Error::last_error(self).unwrap())
+            } else {
+                Ok(Sftp::from_raw(self, ptr))
+            }
+        }
+    }
+
     /// Begin transport layer protocol negotiation with the connected host.
     ///
     /// The socket provided is a connected socket descriptor. Typically a TCP
@@ -264,6 +357,58 @@ impl Session {
                           raw::LIBSSH2_CHANNEL_PACKET_DEFAULT as uint, None)
     }
 
+    /// Open a tunnel through the remote host to a third-party `host:port`,
+    /// as seen from the remote host.
+    ///
+    /// `src` optionally describes the originating address as it should be
+    /// reported to the remote host; when absent, `0.0.0.0` port 0 is
+    /// reported.
+    pub fn channel_direct_tcpip(&self, host: &str, port: u16,
+                                src: Option<(&str, u16)>)
+                                -> Result<Channel, Error> {
+        let host = host.to_c_str();
+        let (shost, sport) = src.unwrap_or(("0.0.0.0", 0));
+        let shost = shost.to_c_str();
+        let ret = unsafe {
+            raw::libssh2_channel_direct_tcpip_ex(self.raw,
+                                                 host.as_ptr(),
+                                                 port as c_int,
+                                                 shost.as_ptr(),
+                                                 sport as c_int)
+        };
+        if ret.is_null() {
+            Err(Error::last_error(self).unwrap())
+        } else {
+            Ok(unsafe { Channel::from_raw(self, ret) })
+        }
+    }
+
+    /// Ask the remote host to listen for incoming TCP connections on
+    /// `remote_port` and forward them back over the session.
+    ///
+    /// Returns a `Listener` to `accept` forwarded connections on, along
+    /// with the port the remote host actually bound (useful when
+    /// `remote_port` is 0, meaning "pick one").
+    pub fn channel_forward_listen(&self, remote_port: u16, host: Option<&str>,
+                                  queue_maxsize: Option<uint>)
+                                  -> Result<(Listener, u16), Error> {
+        let host = host.map(|h| h.to_c_str());
+        let mut bound_port = remote_port as c_int;
+        let ret = unsafe {
+            raw::libssh2_channel_forward_listen_ex(
+                self.raw,
+                host.as_ref().map(|h| h.as_ptr()).unwrap_or(0 as *const _) as *mut _,
+                remote_port as c_int,
+                &mut bound_port,
+                queue_maxsize.unwrap_or(0) as c_int)
+        };
+        if ret.is_null() {
+            Err(Error::last_error(self).unwrap())
+        } else {
+            Ok((unsafe { Listener::from_raw(self, ret) }, bound_port as u16))
+        }
+    }
+
     /// Indicates whether or not the named session has been successfully
     /// authenticated.
     pub fn authenticated(&self) -> bool {
@@ -294,6 +439,189 @@ impl Session {
         }
     }
 
+    /// Authenticate via a plaintext password.
+    ///
+    /// No password-change callback is registered, so if the remote end
+    /// rejects `password` as expired no change is attempted and the
+    /// failure is returned as an error.
+    pub fn userauth_password(&self, username: &str, password: &str)
+                             -> Result<(), Error> {
+        let username_len = username.len();
+        let username = username.to_c_str();
+        let password_len = password.len();
+        let password = password.to_c_str();
+        unsafe {
+            self.rc(raw::libssh2_userauth_password_ex(self.raw,
+                                                       username.as_ptr(),
+                                                       username_len as c_uint,
+                                                       password.as_ptr(),
+                                                       password_len as c_uint,
+                                                       None))
+        }
+    }
+
+    /// Authenticate using a key from a local file, reading the public and
+    /// private key out of files on disk.
+    ///
+    /// The `pubkey` parameter may be omitted if the underlying libssh2
+    /// implementation is capable of extracting the public key from the
+    /// private key file itself.
+    pub fn userauth_pubkey_file(&self,
+                                username: &str,
+                                pubkey: Option<&Path>,
+                                privatekey: &Path,
+                                passphrase: Option<&str>) -> Result<(), Error> {
+        let username_len = username.len();
+        let username = username.to_c_str();
+        let pubkey = pubkey.map(|p| p.to_c_str());
+        let privatekey = privatekey.to_c_str();
+        let passphrase = passphrase.map(|s| s.to_c_str());
+        unsafe {
+            self.rc(raw::libssh2_userauth_publickey_fromfile_ex(
+                        self.raw,
+                        username.as_ptr(),
+                        username_len as c_uint,
+                        pubkey.as_ref().map(|p| p.as_ptr())
+                              .unwrap_or(0 as *const _),
+                        privatekey.as_ptr(),
+                        passphrase.as_ref().map(|p| p.as_ptr())
+                                  .unwrap_or(0 as *const _)))
+        }
+    }
+
+    /// Authenticate using a key pair held entirely in memory rather than on
+    /// disk.
+    pub fn userauth_pubkey_memory(&self,
+                                  username: &str,
+                                  pubkeydata: &str,
+                                  privatekeydata: &str,
+                                  passphrase: Option<&str>) -> Result<(), Error> {
+        let username_len = username.len();
+        let username = username.to_c_str();
+        let passphrase = passphrase.map(|s| s.to_c_str());
+        unsafe {
+            self.rc(raw::libssh2_userauth_publickey_frommemory(
+                        self.raw,
+                        username.as_ptr(),
+                        username_len as c_uint,
+                        pubkeydata.as_ptr() as *const _,
+                        pubkeydata.len() as ::libc::size_t,
+                        privatekeydata.as_ptr() as *const _,
+                        privatekeydata.len() as ::libc::size_t,
+                        passphrase.as_ref().map(|p| p.as_ptr())
+                                  .unwrap_or(0 as *const _)))
+        }
+    }
+
+    /// Authenticate via the "keyboard-interactive" method, answering the
+    /// server's prompts with `prompter`.
+    ///
+    /// The server drives the conversation: it may send any number of named
+    /// prompt sets, each with a name, an instruction, and a list of
+    /// prompts that must be answered (optionally with echo suppressed, for
+    /// e.g. a password). `prompter` is invoked once per prompt set and
+    /// must return exactly one response per prompt.
+    pub fn userauth_keyboard_interactive(&self,
+                                         username: &str,
+                                         prompter: &mut KeyboardInteractivePrompt)
+                                         -> Result<(), Error> {
+        let username_len = username.len();
+        let username = username.to_c_str();
+
+        let mut prompter = prompter as &mut KeyboardInteractivePrompt;
+        unsafe {
+            let abstrakt = raw::libssh2_session_abstract(self.raw);
+            let prev = *abstrakt;
+            *abstrakt = &mut prompter as *mut _ as *mut c_void;
+
+            let rc = raw::libssh2_userauth_keyboard_interactive_ex(
+                        self.raw,
+                        username.as_ptr(),
+                        username_len as c_uint,
+                        kbd_callback);
+
+            *abstrakt = prev;
+            self.rc(rc)
+        }
+    }
+
+    /// Open a channel and request a remote file via SCP, returning both the
+    /// channel to read the file's contents from and the file's stat
+    /// information so the caller knows how many bytes to expect.
+    pub fn scp_recv(&self, path: &Path) -> Result<(Channel, FileStat), Error> {
+        let path = path.to_c_str();
+        unsafe {
+            let mut sb: raw::libssh2_struct_stat = mem::zeroed();
+            let ret = raw::libssh2_scp_recv2(self.raw, path.as_ptr(), &mut sb);
+            if ret.is_null() {
+                Err(Error::last_error(self).unwrap())
+            } else {
+                let stat = FileStat {
+                    size: Some(sb.st_size as u64),
+                    uid: Some(sb.st_uid as uint),
+                    gid: Some(sb.st_gid as uint),
+                    perm: Some(sb.st_mode as uint),
+                    atime: Some(sb.st_atime as u64),
+                    mtime: Some(sb.st_mtime as u64),
+                };
+                Ok((Channel::from_raw(self, ret), stat))
+            }
+        }
+    }
+
+    /// Open a channel and send a local file to the remote host via SCP.
+    ///
+    /// `size` must be known up front so the remote end can be told how
+    /// many bytes to expect; `times`, if given, is an `(atime, mtime)` pair
+    /// to preserve on the remote file.
+    pub fn scp_send(&self, path: &Path, mode: c_int, size: u64,
+                    times: Option<(u64, u64)>) -> Result<Channel, Error> {
+        let path = path.to_c_str();
+        let (atime, mtime) = times.unwrap_or((0, 0));
+        unsafe {
+            let ret = raw::libssh2_scp_send64(self.raw,
+                                              path.as_ptr(),
+                                              mode,
+                                              size as raw::libssh2_uint64_t,
+                                              mtime as c_long,
+                                              atime as c_long);
+            if ret.is_null() {
+                Err(Error::last_error(self).unwrap())
+            } else {
+                Ok(Channel::from_raw(self, ret))
+            }
+        }
+    }
+
+    /// Configure the keepalive settings for this session.
+    ///
+    /// `want_reply` indicates whether the keepalive messages sent by
+    /// `keepalive_send` should request a response from the server (useful
+    /// for detecting a dead peer), and `interval_secs` is how often they
+    /// should be sent. A zero interval disables keepalives, which is the
+    /// default.
+    pub fn keepalive_set(&self, want_reply: bool, interval_secs: uint) {
+        unsafe {
+            raw::libssh2_keepalive_config(self.raw,
+                                          want_reply as c_int,
+                                          interval_secs as c_uint)
+        }
+    }
+
+    /// Send a keepalive message if one is due.
+    ///
+    /// Returns the number of seconds the caller should wait before calling
+    /// this again, so a long-lived idle connection doesn't get dropped by
+    /// a NAT gateway or idle-timeout firewall.
+    pub fn keepalive_send(&self) -> Result<uint, Error> {
+        let mut seconds_to_next = 0;
+        unsafe {
+            try!(self.rc(raw::libssh2_keepalive_send(self.raw,
+                                                      &mut seconds_to_next)));
+        }
+        Ok(seconds_to_next as uint)
+    }
+
     /// Gain access to the underlying raw libssh2 session pointer.
     pub fn raw(&self) -> *mut raw::LIBSSH2_SESSION { self.raw }
 
@@ -310,6 +638,125 @@ impl Session {
     }
 }
 
+/// A single prompt presented by the server during keyboard-interactive
+/// authentication.
+pub struct Prompt {
+    /// The text of the prompt, to be displayed to the user.
+    pub text: String,
+    /// Whether the user's response should be echoed back as typed. This is
+    /// `false` for things like passwords.
+    pub echo: bool,
+}
+
+/// A type which can answer the server's prompts during keyboard-interactive
+/// authentication.
+///
+/// Implementations are handed each named prompt set the server sends and
+/// must return exactly one response string per prompt, in order.
+pub trait KeyboardInteractivePrompt {
+    /// Called once per round-trip with the server's prompts.
+    fn prompt(&mut self, name: &str, instructions: &str,
+              prompts: &[Prompt]) -> Vec<String>;
+}
+
+unsafe fn slice_from_raw<'a>(data: *const u8, len: uint) -> &'a [u8] {
+    mem::transmute(stdraw::Slice { data: data, len: len })
+}
+
+/// Reconciles what a `KeyboardInteractivePrompt` returned with the number
+/// of prompts the server actually asked.
+///
+/// The server hands the callback a fixed-size `responses` array sized to
+/// `num_prompts`, so returning more answers than that can never be
+/// written back safely, and returning fewer would otherwise leave the
+/// remaining prompts silently answered with blanks. Anything other than
+/// an exact match therefore discards all of the returned answers: every
+/// prompt is answered with an empty response instead, so no answer is
+/// written out of bounds or paired with the wrong prompt. Whether the
+/// empty responses are rejected is up to the server; a rejection surfaces
+/// as a normal auth failure through `rc`.
+fn kbd_responses(answers: Vec<String>, num_prompts: uint) -> Vec<String> {
+    if answers.len() == num_prompts {
+        answers
+    } else {
+        Vec::from_fn(num_prompts, |_| String::new())
+    }
+}
+
+extern "C" fn kbd_callback(name: *const c_char,
+                           name_len: c_int,
+                           instruction: *const c_char,
+                           instruction_len: c_int,
+                           num_prompts: c_int,
+                           prompts: *const raw::LIBSSH2_USERAUTH_KBDINT_PROMPT,
+                           responses: *mut raw::LIBSSH2_USERAUTH_KBDINT_RESPONSE,
+                           abstrakt: *mut *mut c_void) {
+    unsafe {
+        let name = str::from_utf8(slice_from_raw(name as *const u8,
+                                                  name_len as uint)).unwrap_or("");
+        let instruction = str::from_utf8(slice_from_raw(instruction as *const u8,
+                                                         instruction_len as uint))
+                               .unwrap_or("");
+
+        let raw_prompts: &[raw::LIBSSH2_USERAUTH_KBDINT_PROMPT] =
+            mem::transmute(stdraw::Slice {
+                data: prompts,
+                len: num_prompts as uint,
+            });
+        let reqs: Vec<Prompt> = raw_prompts.iter().map(|p| {
+            let text = slice_from_raw(p.text as *const u8, p.length as uint);
+            Prompt {
+                text: String::from_utf8_lossy(text).into_owned(),
+                echo: p.echo != 0,
+            }
+        }).collect();
+
+        let responses: &mut [raw::LIBSSH2_USERAUTH_KBDINT_RESPONSE] =
+            mem::transmute(stdraw::Slice {
+                data: responses as *const _,
+                len: num_prompts as uint,
+            });
+
+        let prompter: &mut &mut KeyboardInteractivePrompt = mem::transmute(*abstrakt);
+        let answers = prompter.prompt(name, instruction, reqs.as_slice());
+        let answers = kbd_responses(answers, reqs.len());
+
+        for (i, answer) in answers.iter().enumerate() {
+            let buf = ::libc::malloc(answer.len() as ::libc::size_t) as *mut c_char;
+            if answer.len() > 0 {
+                ptr::copy_nonoverlapping_memory(buf, answer.as_ptr() as *const c_char,
+                                                 answer.len());
+            }
+            responses[i].text = buf;
+            responses[i].length = answer.len() as c_uint;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::kbd_responses;
+
+    #[test]
+    fn kbd_responses_exact_match_passes_through() {
+        let answers = vec!["secret".to_string()];
+        assert_eq!(kbd_responses(answers.clone(), 1), answers);
+    }
+
+    #[test]
+    fn kbd_responses_too_many_becomes_all_empty() {
+        let answers = vec!["a".to_string(), "b".to_string()];
+        assert_eq!(kbd_responses(answers, 1), vec!["".to_string()]);
+    }
+
+    #[test]
+    fn kbd_responses_too_few_becomes_all_empty() {
+        let answers = vec![];
+        assert_eq!(kbd_responses(answers, 2),
+                   vec!["".to_string(), "".to_string()]);
+    }
+}
+
 impl Drop for Session {
     fn drop(&mut self) {
         unsafe {
@@ -0,0 +1,421 @@
+use std::mem;
+use std::raw as stdraw;
+use std::io::{Reader, Writer, IoResult, IoError, OtherIoError};
+use libc::{c_uint, c_int, c_long, size_t};
+
+use {raw, Error, Session};
+
+/// A handle to a remote SFTP session, layered on top of a `Session`.
+///
+/// Created via `Session::sftp`.
+pub struct Sftp<'sess> {
+    raw: *mut raw::LIBSSH2_SFTP,
+    sess: &'sess Session,
+}
+
+/// A handle to an open remote file or directory, obtained from `Sftp`.
+pub struct File<'sftp> {
+    raw: *mut raw::LIBSSH2_SFTP_HANDLE,
+    sftp: &'sftp Sftp<'sftp>,
+}
+
+/// Metadata about a remote file, as returned by `stat`-family calls.
+///
+/// Each field is `None` if the server did not provide that piece of
+/// information.
+#[deriving(Clone)]
+pub struct FileStat {
+    pub size: Option<u64>,
+    pub uid: Option<uint>,
+    pub gid: Option<uint>,
+    pub perm: Option<uint>,
+    pub atime: Option<u64>,
+    pub mtime: Option<u64>,
+}
+
+/// A single entry returned while iterating a directory with `readdir`.
+pub struct DirEntry {
+    /// The entry's filename, relative to the directory being listed.
+    pub filename: String,
+    /// The metadata libssh2 returned alongside the filename.
+    pub stat: FileStat,
+}
+
+impl<'sess> Sftp<'sess> {
+    /// Takes ownership of the given raw pointer and wraps it, tying it to
+    /// the lifetime of the session provided.
+    ///
+    /// This is unsafe as there is no guarantee about the validity of `raw`.
+    pub unsafe fn from_raw(sess: &'sess Session,
+                           raw: *mut raw::LIBSSH2_SFTP) -> Sftp<'sess> {
+        Sftp {
+            raw: raw,
+            sess: sess,
+        }
+    }
+
+    /// Open a handle to a file for reading.
+    pub fn open(&self, filename: &str) -> Result<File, Error> {
+        self.open_mode(filename,
+                       raw::LIBSSH2_FXF_READ,
+                       0o644,
+                       raw::LIBSSH2_SFTP_OPENFILE)
+    }
+
+    /// Create (or truncate) a file and open a handle to it for writing.
+    pub fn create(&self, filename: &str) -> Result<File, Error> {
+        self.open_mode(filename,
+                       raw::LIBSSH2_FXF_WRITE | raw::LIBSSH2_FXF_CREAT |
+                       raw::LIBSSH2_FXF_TRUNC,
+                       0o644,
+                       raw::LIBSSH2_SFTP_OPENFILE)
+    }
+
+    /// Open a handle to a directory for iteration with `readdir`.
+    pub fn opendir(&self, dirname: &str) -> Result<File, Error> {
+        self.open_mode(dirname, 0, 0, raw::LIBSSH2_SFTP_OPENDIR)
+    }
+
+    /// Lower-level open, exposing the raw SFTP open flags, permission mode,
+    /// and whether a file or directory handle is being requested.
+    pub fn open_mode(&self, filename: &str, flags: c_int, mode: c_int,
+                     open_type: c_int) -> Result<File, Error> {
+        let filename_len = filename.len();
+        let filename = filename.to_c_str();
+        unsafe {
+            let ret = raw::libssh2_sftp_open_ex(self.raw,
+                                                filename.as_ptr(),
+                                                filename_len as c_uint,
+                                                flags,
+                                                mode,
+                                                open_type);
+            if ret.is_null() {
+                Err(Error::last_error(self.sess).unwrap())
+            } else {
+                Ok(File::from_raw(self, ret))
+            }
+        }
+    }
+
+    /// Helper to issue a bare `stat`/`lstat`/`setstat` call.
+    fn stat_ex(&self, path: &str, stat_type: c_int,
+              attrs: *mut raw::LIBSSH2_SFTP_ATTRIBUTES) -> Result<(), Error> {
+        let path_len = path.len();
+        let path = path.to_c_str();
+        unsafe {
+            self.rc(raw::libssh2_sftp_stat_ex(self.raw,
+                                              path.as_ptr(),
+                                              path_len as c_uint,
+                                              stat_type,
+                                              attrs))
+        }
+    }
+
+    /// Get metadata about a remote path, following symlinks.
+    pub fn stat(&self, path: &str) -> Result<FileStat, Error> {
+        let mut attrs: raw::LIBSSH2_SFTP_ATTRIBUTES = unsafe { mem::zeroed() };
+        try!(self.stat_ex(path, raw::LIBSSH2_SFTP_STAT, &mut attrs));
+        Ok(stat_from_raw(&attrs))
+    }
+
+    /// Get metadata about a remote path, without following a final
+    /// symlink.
+    pub fn lstat(&self, path: &str) -> Result<FileStat, Error> {
+        let mut attrs: raw::LIBSSH2_SFTP_ATTRIBUTES = unsafe { mem::zeroed() };
+        try!(self.stat_ex(path, raw::LIBSSH2_SFTP_LSTAT, &mut attrs));
+        Ok(stat_from_raw(&attrs))
+    }
+
+    /// Set metadata on a remote path.
+    pub fn setstat(&self, path: &str, stat: &FileStat) -> Result<(), Error> {
+        let mut attrs = raw_from_stat(stat);
+        self.stat_ex(path, raw::LIBSSH2_SFTP_SETSTAT, &mut attrs)
+    }
+
+    /// Create a remote directory.
+    pub fn mkdir(&self, path: &str, mode: c_int) -> Result<(), Error> {
+        let path_len = path.len();
+        let path = path.to_c_str();
+        unsafe {
+            self.rc(raw::libssh2_sftp_mkdir_ex(self.raw, path.as_ptr(),
+                                               path_len as c_uint, mode))
+        }
+    }
+
+    /// Remove an empty remote directory.
+    pub fn rmdir(&self, path: &str) -> Result<(), Error> {
+        let path_len = path.len();
+        let path = path.to_c_str();
+        unsafe {
+            self.rc(raw::libssh2_sftp_rmdir_ex(self.raw, path.as_ptr(),
+                                               path_len as c_uint))
+        }
+    }
+
+    /// Remove a remote file.
+    pub fn unlink(&self, path: &str) -> Result<(), Error> {
+        let path_len = path.len();
+        let path = path.to_c_str();
+        unsafe {
+            self.rc(raw::libssh2_sftp_unlink_ex(self.raw, path.as_ptr(),
+                                                path_len as c_uint))
+        }
+    }
+
+    /// Rename (or move) a remote file.
+    pub fn rename(&self, src: &str, dst: &str) -> Result<(), Error> {
+        let src_len = src.len();
+        let src = src.to_c_str();
+        let dst_len = dst.len();
+        let dst = dst.to_c_str();
+        unsafe {
+            self.rc(raw::libssh2_sftp_rename_ex(self.raw,
+                                                src.as_ptr(), src_len as c_uint,
+                                                dst.as_ptr(), dst_len as c_uint,
+                                                raw::LIBSSH2_SFTP_RENAME_OVERWRITE |
+                                                raw::LIBSSH2_SFTP_RENAME_ATOMIC |
+                                                raw::LIBSSH2_SFTP_RENAME_NATIVE))
+        }
+    }
+
+    /// Create a symlink at `path` pointing to `target`.
+    pub fn symlink(&self, path: &str, target: &str) -> Result<(), Error> {
+        let path_len = path.len();
+        let path = path.to_c_str();
+        let target_len = target.len();
+        let target = target.to_c_str();
+        unsafe {
+            self.rc(raw::libssh2_sftp_symlink_ex(self.raw,
+                                                 path.as_ptr(), path_len as c_uint,
+                                                 target.as_ptr() as *mut _,
+                                                 target_len as c_uint,
+                                                 raw::LIBSSH2_SFTP_SYMLINK))
+        }
+    }
+
+    /// Read the target of a symlink at `path`.
+    pub fn readlink(&self, path: &str) -> Result<String, Error> {
+        let path_len = path.len();
+        let path = path.to_c_str();
+        let mut buf = [0u8, ..1024];
+        unsafe {
+            let rc = raw::libssh2_sftp_symlink_ex(self.raw,
+                                                  path.as_ptr(), path_len as c_uint,
+                                                  buf.as_mut_ptr() as *mut _,
+                                                  buf.len() as c_uint,
+                                                  raw::LIBSSH2_SFTP_READLINK);
+            if rc < 0 {
+                // Go straight to the session's last recorded error instead
+                // of `self.rc`: `rc` is known-negative here, but `rc()`
+                // treats "no recorded error" as success, which would fall
+                // through to `slice_to(rc as uint)` below with a negative
+                // value cast to a huge length and panic.
+                return Err(Error::last_error(self.sess).unwrap());
+            }
+            let target = ::std::str::from_utf8(buf.slice_to(rc as uint)).unwrap_or("");
+            Ok(target.to_string())
+        }
+    }
+
+    fn rc(&self, rc: c_int) -> Result<(), Error> { self.sess.rc(rc) }
+}
+
+fn stat_from_raw(attrs: &raw::LIBSSH2_SFTP_ATTRIBUTES) -> FileStat {
+    let has = |flag: c_uint| attrs.flags & flag != 0;
+    FileStat {
+        size: if has(raw::LIBSSH2_SFTP_ATTR_SIZE) {
+            Some(attrs.filesize as u64)
+        } else { None },
+        uid: if has(raw::LIBSSH2_SFTP_ATTR_UIDGID) {
+            Some(attrs.uid as uint)
+        } else { None },
+        gid: if has(raw::LIBSSH2_SFTP_ATTR_UIDGID) {
+            Some(attrs.gid as uint)
+        } else { None },
+        perm: if has(raw::LIBSSH2_SFTP_ATTR_PERMISSIONS) {
+            Some(attrs.permissions as uint)
+        } else { None },
+        atime: if has(raw::LIBSSH2_SFTP_ATTR_ACMODTIME) {
+            Some(attrs.atime as u64)
+        } else { None },
+        mtime: if has(raw::LIBSSH2_SFTP_ATTR_ACMODTIME) {
+            Some(attrs.mtime as u64)
+        } else { None },
+    }
+}
+
+fn raw_from_stat(stat: &FileStat) -> raw::LIBSSH2_SFTP_ATTRIBUTES {
+    let mut attrs: raw::LIBSSH2_SFTP_ATTRIBUTES = unsafe { mem::zeroed() };
+    if let Some(size) = stat.size {
+        attrs.flags |= raw::LIBSSH2_SFTP_ATTR_SIZE;
+        attrs.filesize = size as raw::libssh2_uint64_t;
+    }
+    if let (Some(uid), Some(gid)) = (stat.uid, stat.gid) {
+        attrs.flags |= raw::LIBSSH2_SFTP_ATTR_UIDGID;
+        attrs.uid = uid as c_long;
+        attrs.gid = gid as c_long;
+    }
+    if let Some(perm) = stat.perm {
+        attrs.flags |= raw::LIBSSH2_SFTP_ATTR_PERMISSIONS;
+        attrs.permissions = perm as c_long;
+    }
+    if let (Some(atime), Some(mtime)) = (stat.atime, stat.mtime) {
+        attrs.flags |= raw::LIBSSH2_SFTP_ATTR_ACMODTIME;
+        attrs.atime = atime as c_long;
+        attrs.mtime = mtime as c_long;
+    }
+    attrs
+}
+
+impl<'sftp> File<'sftp> {
+    /// Takes ownership of the given raw pointer and wraps it, tying it to
+    /// the lifetime of the `Sftp` handle that opened it.
+    pub unsafe fn from_raw(sftp: &'sftp Sftp, raw: *mut raw::LIBSSH2_SFTP_HANDLE)
+                           -> File<'sftp> {
+        File {
+            raw: raw,
+            sftp: sftp,
+        }
+    }
+
+    /// Get metadata about this already-open file.
+    pub fn stat(&self) -> Result<FileStat, Error> {
+        let mut attrs: raw::LIBSSH2_SFTP_ATTRIBUTES = unsafe { mem::zeroed() };
+        unsafe {
+            try!(self.sftp.rc(raw::libssh2_sftp_fstat_ex(self.raw, &mut attrs, 0)));
+        }
+        Ok(stat_from_raw(&attrs))
+    }
+
+    /// Read the next directory entry from a handle opened with `opendir`.
+    ///
+    /// Returns `None` once the directory has been fully enumerated.
+    pub fn readdir(&mut self) -> Option<Result<DirEntry, Error>> {
+        let mut buf = [0u8, ..1024];
+        let mut attrs: raw::LIBSSH2_SFTP_ATTRIBUTES = unsafe { mem::zeroed() };
+        unsafe {
+            let rc = raw::libssh2_sftp_readdir_ex(self.raw,
+                                                  buf.as_mut_ptr() as *mut _,
+                                                  buf.len() as size_t,
+                                                  0 as *mut _,
+                                                  0,
+                                                  &mut attrs);
+            if rc == 0 {
+                None
+            } else if rc < 0 {
+                match self.sftp.rc(rc as c_int) {
+                    Ok(()) => None,
+                    Err(e) => Some(Err(e)),
+                }
+            } else {
+                let filename = ::std::str::from_utf8(buf.slice_to(rc as uint))
+                                   .unwrap_or("").to_string();
+                Some(Ok(DirEntry {
+                    filename: filename,
+                    stat: stat_from_raw(&attrs),
+                }))
+            }
+        }
+    }
+}
+
+impl<'sftp> Reader for File<'sftp> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        let rc = unsafe {
+            raw::libssh2_sftp_read(self.raw, buf.as_mut_ptr() as *mut _,
+                                   buf.len() as size_t)
+        };
+        if rc < 0 {
+            Err(IoError {
+                kind: OtherIoError,
+                desc: "sftp read failed",
+                detail: self.sftp.rc(rc as c_int).err().map(|e| e.to_string()),
+            })
+        } else if rc == 0 {
+            Err(::std::io::standard_error(::std::io::EndOfFile))
+        } else {
+            Ok(rc as uint)
+        }
+    }
+}
+
+impl<'sftp> Writer for File<'sftp> {
+    fn write(&mut self, buf: &[u8]) -> IoResult<()> {
+        let mut written = 0u;
+        while written < buf.len() {
+            let rc = unsafe {
+                raw::libssh2_sftp_write(self.raw,
+                                        buf[written..].as_ptr() as *const _,
+                                        (buf.len() - written) as size_t)
+            };
+            if rc < 0 {
+                return Err(IoError {
+                    kind: OtherIoError,
+                    desc: "sftp write failed",
+                    detail: self.sftp.rc(rc as c_int).err().map(|e| e.to_string()),
+                });
+            }
+            written += rc as uint;
+        }
+        Ok(())
+    }
+}
+
+#[unsafe_destructor]
+impl<'sftp> Drop for File<'sftp> {
+    fn drop(&mut self) {
+        unsafe { raw::libssh2_sftp_close_handle(self.raw); }
+    }
+}
+
+#[unsafe_destructor]
+impl<'sess> Drop for Sftp<'sess> {
+    fn drop(&mut self) {
+        unsafe { raw::libssh2_sftp_shutdown(self.raw); }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileStat, stat_from_raw, raw_from_stat};
+
+    #[test]
+    fn stat_round_trip_full() {
+        let stat = FileStat {
+            size: Some(1234),
+            uid: Some(1000),
+            gid: Some(100),
+            perm: Some(0o644),
+            atime: Some(111),
+            mtime: Some(222),
+        };
+        let raw = raw_from_stat(&stat);
+        let back = stat_from_raw(&raw);
+        assert_eq!(back.size, stat.size);
+        assert_eq!(back.uid, stat.uid);
+        assert_eq!(back.gid, stat.gid);
+        assert_eq!(back.perm, stat.perm);
+        assert_eq!(back.atime, stat.atime);
+        assert_eq!(back.mtime, stat.mtime);
+    }
+
+    #[test]
+    fn stat_round_trip_partial_leaves_unset_fields_none() {
+        let stat = FileStat {
+            size: Some(1),
+            uid: None,
+            gid: None,
+            perm: None,
+            atime: None,
+            mtime: None,
+        };
+        let raw = raw_from_stat(&stat);
+        let back = stat_from_raw(&raw);
+        assert_eq!(back.size, Some(1));
+        assert_eq!(back.uid, None);
+        assert_eq!(back.gid, None);
+        assert_eq!(back.perm, None);
+        assert_eq!(back.atime, None);
+        assert_eq!(back.mtime, None);
+    }
+}